@@ -0,0 +1,88 @@
+use crate::{Nonce, ResourceKind};
+use serde::Serialize;
+use std::sync::mpsc;
+#[cfg(feature = "events")]
+use std::thread;
+use std::time::SystemTime;
+
+/// Fine-grained mining activity, emitted independently of the human-readable status line.
+/// Mirrors the event-emitter pattern used in Kindelia's node.
+// `emit_event!` is a true no-op without the `events` feature, so these variants (and the
+// fields that construct them, e.g. `Block::seed_hex`) are legitimately unconstructed in that
+// build rather than a bug.
+#[cfg_attr(not(feature = "events"), allow(dead_code))]
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type")]
+pub(crate) enum NodeEventType {
+    MiningStarted {
+        threads: usize,
+    },
+    BlockExhausted {
+        block_index: usize,
+        seed_hex: String,
+    },
+    ResourceFound {
+        kind: ResourceKind,
+        hash: String,
+        nonce: Nonce,
+    },
+    Retarget {
+        kind: ResourceKind,
+        new_target: String,
+    },
+}
+
+/// The dedicated channel node events travel over, paired with the microsecond timestamp
+/// they were emitted at.
+pub(crate) type EventSender = mpsc::Sender<(NodeEventType, u128)>;
+pub(crate) type EventReceiver = mpsc::Receiver<(NodeEventType, u128)>;
+
+pub(crate) fn channel() -> (EventSender, EventReceiver) {
+    mpsc::channel()
+}
+
+#[cfg_attr(not(feature = "events"), allow(dead_code))]
+pub(crate) fn get_time_micro() -> u128 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_micros()
+}
+
+/// Sends `$event` over `$tx` tagged with the current microsecond timestamp. Compiles away
+/// to nothing when the `events` feature is off, so disabled builds pay no cost to
+/// construct events in hot paths.
+#[macro_export]
+macro_rules! emit_event {
+    ($tx:expr, $event:expr) => {{
+        #[cfg(feature = "events")]
+        {
+            let _ = $tx.send(($event, $crate::events::get_time_micro()));
+        }
+        #[cfg(not(feature = "events"))]
+        {
+            let _ = &$tx;
+        }
+    }};
+}
+
+#[cfg(feature = "events")]
+#[derive(Serialize)]
+struct EventLine {
+    micros: u128,
+    #[serde(flatten)]
+    event: NodeEventType,
+}
+
+/// Built-in subscriber selected with `-output=json`: renders every event as a JSON line
+/// on stdout so the miner can feed metrics pipelines.
+#[cfg(feature = "events")]
+pub(crate) fn spawn_json_subscriber(rx: EventReceiver) {
+    thread::spawn(move || {
+        for (event, micros) in rx {
+            if let Ok(line) = serde_json::to_string(&EventLine { micros, event }) {
+                println!("{}", line);
+            }
+        }
+    });
+}
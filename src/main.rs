@@ -1,19 +1,29 @@
 use bigint::U256;
-use core::convert::TryFrom;
 use rand::Rng;
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::io::prelude::*;
 use std::sync::{mpsc, Arc, Mutex};
 use std::thread;
 use std::time::SystemTime;
 
+mod events;
+mod net;
+mod storage;
+
+use events::EventSender;
+
 const CHUNK_SIZE: u32 = 50000;
 
 type Nonce = u32;
-type ResourceTarget = [u8; 32];
+type ResourceTarget = U256;
 type Args = HashMap<String, String>;
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+// Variant casing is load-bearing: it's how ResourceKind serializes (mined.txt, miner.state,
+// the net.rs wire protocol), so renaming to appease clippy would break compatibility with
+// existing saved state and logs.
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 enum ResourceKind {
     COAL,
     IRON,
@@ -21,6 +31,38 @@ enum ResourceKind {
     DIAMOND,
 }
 
+impl ResourceKind {
+    /// Lowercase name used to key `-<kind>-interval-ms` arguments.
+    fn arg_key(&self) -> &'static str {
+        match self {
+            ResourceKind::COAL => "coal",
+            ResourceKind::IRON => "iron",
+            ResourceKind::GOLD => "gold",
+            ResourceKind::DIAMOND => "diamond",
+        }
+    }
+
+    /// Default desired average time between finds, used when `-<kind>-interval-ms` is unset.
+    fn default_interval_ms(&self) -> u128 {
+        match self {
+            ResourceKind::COAL => 750,
+            ResourceKind::IRON => 1500,
+            ResourceKind::GOLD => 3000,
+            ResourceKind::DIAMOND => 5000,
+        }
+    }
+
+    /// Default target, as a little-endian hex string, used when `-<kind>-bits` is unset.
+    fn default_target_hex(&self) -> &'static str {
+        match self {
+            ResourceKind::COAL => "0000003fffff",
+            ResourceKind::IRON => "0000000fffff",
+            ResourceKind::GOLD => "00000002ffff",
+            ResourceKind::DIAMOND => "000000000fff",
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 struct Resource {
     target: ResourceTarget,
@@ -28,58 +70,175 @@ struct Resource {
 }
 
 impl Resource {
-    fn new(kind: ResourceKind, v: &str) -> Self {
+    fn from_hex(kind: ResourceKind, v: &str) -> Self {
         let mut s_buf = hex::decode(v).unwrap();
         s_buf.reverse();
 
-        let n = U256::try_from(s_buf.as_slice()).unwrap();
-
-        let mut n_buf = [0; 32];
-        n.to_big_endian(&mut n_buf);
+        Self {
+            target: U256::from(s_buf.as_slice()),
+            kind,
+        }
+    }
 
+    /// Builds a target from a leading-zero-bit difficulty, the conventional PoW way to
+    /// express it: `target = U256::MAX >> bits`.
+    fn from_bits(kind: ResourceKind, bits: usize) -> Self {
         Self {
-            target: n_buf,
-            kind: kind,
+            target: U256::max_value() >> bits,
+            kind,
         }
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+/// Tracks how many finds a kind has had since its retargeting window opened.
+#[derive(Debug, Clone)]
+struct RetargetWindow {
+    interval_ms: u128,
+    window_start: u128,
+    count: u64,
+}
+
+/// Number of finds observed before a kind's target is retargeted.
+const RETARGET_WINDOW: u64 = 64;
+
+#[derive(Debug, Clone)]
 struct ResourceMatcher {
     resources: Vec<Resource>,
+    windows: HashMap<ResourceKind, RetargetWindow>,
 }
 
 impl ResourceMatcher {
-    fn new() -> Self {
-        let mut resources = vec![];
-
-        resources.push(Resource::new(ResourceKind::DIAMOND, "000000000fff"));
-        resources.push(Resource::new(ResourceKind::GOLD, "00000002ffff"));
-        resources.push(Resource::new(ResourceKind::IRON, "0000000fffff"));
-        resources.push(Resource::new(ResourceKind::COAL, "0000003fffff"));
-
-        Self {
-            resources: resources,
+    fn new(args: &Args) -> Self {
+        let kinds = [
+            ResourceKind::DIAMOND,
+            ResourceKind::GOLD,
+            ResourceKind::IRON,
+            ResourceKind::COAL,
+        ];
+
+        let resources: Vec<Resource> = kinds
+            .into_iter()
+            .map(|kind| match args.get(&format!("{}-bits", kind.arg_key())) {
+                Some(bits) => Resource::from_bits(kind.clone(), bits.parse().unwrap()),
+                None => {
+                    let hex = kind.default_target_hex();
+                    Resource::from_hex(kind, hex)
+                }
+            })
+            .collect();
+
+        let now = get_time_ms();
+        let mut windows = HashMap::new();
+        for resource in resources.iter() {
+            // Floored at 1: `interval_ms * RETARGET_WINDOW` is used as a retarget divisor in
+            // `record_find`, and a 0 there panics (and poisons the shared matcher mutex) as
+            // soon as the window fills.
+            let interval_ms = args
+                .get(&format!("{}-interval-ms", resource.kind.arg_key()))
+                .map(|v| v.parse::<u128>().unwrap().max(1))
+                .unwrap_or_else(|| resource.kind.default_interval_ms());
+
+            windows.insert(
+                resource.kind.clone(),
+                RetargetWindow {
+                    interval_ms,
+                    window_start: now,
+                    count: 0,
+                },
+            );
         }
+
+        Self { resources, windows }
     }
 
+    /// Returns the hardest resource (resources are checked in hardest-first order) whose
+    /// target `hash` is below, comparing the hash numerically rather than byte-by-byte.
     fn match_hash(&self, hash: &[u8]) -> Option<Resource> {
-        for resource in self.resources.iter() {
-            let mut brk = false;
-            for i in (0..hash.len()).rev() {
-                if hash[i] < resource.target[i] {
-                    return Some(resource.clone());
-                }
-                if hash[i] > resource.target[i] {
-                    brk = true;
-                    break;
-                }
-            }
-            if !brk {
-                return Some(resource.clone());
+        let value = U256::from_big_endian(hash);
+        self.resources
+            .iter()
+            .find(|resource| value < resource.target)
+            .cloned()
+    }
+
+    /// Snapshot of each resource's current target, as big-endian bytes, for the coordinator
+    /// to hand workers in their `Job` so they match against its live targets instead of
+    /// the targets they started with.
+    fn target_snapshot(&self) -> HashMap<ResourceKind, [u8; 32]> {
+        self.resources
+            .iter()
+            .map(|resource| {
+                let mut buf = [0; 32];
+                resource.target.to_big_endian(&mut buf);
+                (resource.kind.clone(), buf)
+            })
+            .collect()
+    }
+
+    /// Overwrites each resource's target with the ones a `Job` just carried from the
+    /// coordinator, so a worker's matching stays aligned with the coordinator's retargeted
+    /// view instead of the targets it started with.
+    fn apply_targets(&mut self, targets: &HashMap<ResourceKind, [u8; 32]>) {
+        for resource in self.resources.iter_mut() {
+            if let Some(buf) = targets.get(&resource.kind) {
+                resource.target = U256::from_big_endian(buf);
             }
         }
-        None
+    }
+
+    /// Records a find for `kind` and, once its window fills up, retargets it so the
+    /// observed find rate drifts back towards `interval_ms`.
+    fn record_find(&mut self, kind: &ResourceKind, now: u128, events: &EventSender) {
+        let window = self.windows.get_mut(kind).unwrap();
+        window.count += 1;
+
+        if window.count < RETARGET_WINDOW {
+            return;
+        }
+
+        let actual = now.saturating_sub(window.window_start).max(1);
+        let expected = window.interval_ms * RETARGET_WINDOW as u128;
+
+        let resource = self
+            .resources
+            .iter_mut()
+            .find(|resource| &resource.kind == kind)
+            .unwrap();
+
+        let old_target = resource.target;
+        // `actual` can run arbitrarily far ahead of `expected` if the real find rate can't
+        // keep up with `interval_ms` for several windows in a row, so the multiply here can
+        // overflow U256 well before the min/max clamp below gets a chance to bound it.
+        let (product, overflowed) = old_target.overflowing_mul(U256::from(actual as u64));
+        let mut new_target = if overflowed {
+            U256::max_value()
+        } else {
+            product / U256::from(expected as u64)
+        };
+
+        let min_target = old_target >> 2;
+        let max_target = old_target << 2;
+        if new_target < min_target {
+            new_target = min_target;
+        }
+        if new_target > max_target {
+            new_target = max_target;
+        }
+
+        resource.target = new_target;
+
+        let mut target_buf = [0; 32];
+        new_target.to_big_endian(&mut target_buf);
+        crate::emit_event!(
+            events,
+            events::NodeEventType::Retarget {
+                kind: kind.clone(),
+                new_target: hex::encode(target_buf),
+            }
+        );
+
+        window.count = 0;
+        window.window_start = now;
     }
 }
 
@@ -104,6 +263,9 @@ impl Block {
         }
     }
 
+    // Only called from the BlockExhausted event, which `emit_event!` never constructs
+    // without the `events` feature.
+    #[cfg_attr(not(feature = "events"), allow(dead_code))]
     fn seed_hex(&self) -> String {
         hex::encode(self.seed)
     }
@@ -134,15 +296,29 @@ impl BlockManager {
         }
     }
 
-    fn get_block(&mut self) -> (Block, u32, u32) {
+    /// Rebuilds a `BlockManager` from a saved checkpoint, resuming each block at its
+    /// saved nonce cursor instead of starting over at nonce 0.
+    fn from_parts(blocks: [(Block, Nonce); 2], mined: u128) -> Self {
+        Self { blocks, mined }
+    }
+
+    fn get_block(&mut self, events: &EventSender) -> (Block, u32, u32) {
         let b = &self.blocks[0].clone();
         let start = b.1;
 
         if start == u32::MAX {
+            crate::emit_event!(
+                events,
+                events::NodeEventType::BlockExhausted {
+                    block_index: self.mined as usize,
+                    seed_hex: b.0.seed_hex(),
+                }
+            );
+
             self.blocks = [self.blocks[1].clone(), (Block::new(), 0)];
             self.mined += 1;
 
-            return self.get_block();
+            return self.get_block(events);
         }
 
         let mut left = u32::MAX - start;
@@ -175,41 +351,148 @@ fn get_args() -> Args {
     args
 }
 
-fn main() {
-    let mut save_file = std::fs::File::create("mined.txt").unwrap();
+fn get_threads(args: &Args) -> usize {
+    if let Some(threads) = args.get("threads") {
+        threads.parse::<usize>().unwrap()
+    } else {
+        num_cpus::get_physical()
+    }
+}
 
-    let args = get_args();
+/// True when `-output=json` was passed, selecting the JSON event subscriber over the
+/// human-readable `\r`-status line. Falls back to the human-readable line (with a warning)
+/// if this binary wasn't built with the `events` feature, since there'd be no subscriber to
+/// print the JSON and the human-readable line would otherwise be silently suppressed too.
+fn is_json_output(args: &Args) -> bool {
+    let requested = args.get("output").map(String::as_str) == Some("json");
+
+    if requested && !cfg!(feature = "events") {
+        eprintln!(
+            "warning: -output=json requires a build with the `events` feature enabled; \
+             falling back to the human-readable status line"
+        );
+        return false;
+    }
+
+    requested
+}
+
+/// Opens `mined.txt` in append mode so found-resource history survives restarts,
+/// recording a header line the first time the file is created.
+fn open_mined_log() -> std::fs::File {
+    let is_new = !std::path::Path::new("mined.txt").exists();
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open("mined.txt")
+        .unwrap();
+
+    if is_new {
+        writeln!(file, "# time kind hash").unwrap();
+    }
+
+    file
+}
+
+/// Drains found resources off `rx`, accumulating per-kind `mined` totals, appending each hit
+/// to `save_file`, and (outside JSON mode) printing a live `\r`-status line. `checkpoint`,
+/// when set, is the `BlockManager`/`BlockStorage` pair local mode uses to persist progress
+/// after every find and to report how many blocks have been mined; worker mode has neither.
+fn run_reporter(
+    rx: mpsc::Receiver<(Resource, String)>,
+    mut save_file: std::fs::File,
+    mut mined: HashMap<ResourceKind, u128>,
+    json_output: bool,
+    mut checkpoint: Option<(Arc<Mutex<BlockManager>>, storage::BlockStorage)>,
+) {
+    while let Ok((resource, hash)) = rx.recv() {
+        if let Some(v) = mined.get_mut(&resource.kind) {
+            *v += 1;
+        } else {
+            mined.insert(resource.kind.clone(), 1);
+        }
+
+        save_file
+            .write_fmt(format_args!(
+                "{} {:?} {}\n",
+                get_time_ms(),
+                resource.kind,
+                hash
+            ))
+            .unwrap();
+
+        let blocks_mined = checkpoint.as_mut().map(|(block_manager, storage)| {
+            let block_manager = block_manager.lock().unwrap();
+            storage.checkpoint(&block_manager, &mined);
+            block_manager.mined
+        });
+
+        if !json_output {
+            match blocks_mined {
+                Some(blocks_mined) => print!("\r{:?} BLOCKS: {}", mined, blocks_mined),
+                None => print!("\r{:?}", mined),
+            }
+            std::io::stdout().flush().expect("Couldn't flush stdout!");
+        }
+    }
+}
+
+fn run_local(args: Args) {
+    let save_file = open_mined_log();
+    let json_output = is_json_output(&args);
 
     let (tx, rx) = mpsc::channel();
+    let (event_tx, _event_rx) = events::channel();
+    #[cfg(feature = "events")]
+    if json_output {
+        events::spawn_json_subscriber(_event_rx);
+    }
 
-    let block_manager = Arc::new(Mutex::new(BlockManager::new()));
-    let resource_manager = ResourceMatcher::new();
-    let threads = if let Some(threads) = args.get("threads") {
-        threads.parse::<usize>().unwrap()
-    } else {
-        num_cpus::get_physical()
+    let (block_manager, initial_totals) = match storage::BlockStorage::restore() {
+        Some((block_manager, totals)) => {
+            println!("resuming from saved state");
+            (block_manager, totals)
+        }
+        None => (BlockManager::new(), HashMap::new()),
     };
+    let block_manager = Arc::new(Mutex::new(block_manager));
+    let resource_manager = Arc::new(Mutex::new(ResourceMatcher::new(&args)));
+    let threads = get_threads(&args);
     let mut handles = vec![];
 
     println!("start: {:#?}", get_time_ms());
     println!("threads: {}", threads);
     println!("mining...");
+    crate::emit_event!(event_tx, events::NodeEventType::MiningStarted { threads });
 
     for _ in 0..threads {
         let bm = block_manager.clone();
         let rm = resource_manager.clone();
         let tx = tx.clone();
+        let event_tx = event_tx.clone();
         handles.push(thread::spawn(move || loop {
             let (block, start, end) = {
                 let mut bm = bm.lock().unwrap();
-                bm.get_block()
+                bm.get_block(&event_tx)
             };
 
             let mut n = start;
             loop {
                 let hash = block.hash(n);
 
-                if let Some(resource) = rm.match_hash(hash.as_slice()) {
+                let matched = { rm.lock().unwrap().match_hash(hash.as_slice()) };
+                if let Some(resource) = matched {
+                    rm.lock()
+                        .unwrap()
+                        .record_find(&resource.kind, get_time_ms(), &event_tx);
+                    crate::emit_event!(
+                        event_tx,
+                        events::NodeEventType::ResourceFound {
+                            kind: resource.kind.clone(),
+                            hash: hex::encode(&hash),
+                            nonce: n,
+                        }
+                    );
                     let _ = tx.send((resource, hex::encode(hash)));
                 }
 
@@ -223,41 +506,94 @@ fn main() {
     }
 
     thread::spawn(move || {
-        let mut mined: HashMap<ResourceKind, u128> = HashMap::new();
-        let bm = block_manager.clone();
-
-        loop {
-            match rx.recv() {
-                Ok((resource, hash)) => {
-                    if let Some(v) = mined.get_mut(&resource.kind) {
-                        *v += 1;
-                    } else {
-                        mined.insert(resource.kind.clone(), 1);
-                    }
-
-                    save_file
-                        .write_fmt(format_args!(
-                            "{} {:?} {}\n",
-                            get_time_ms(),
-                            resource.kind,
-                            hash
-                        ))
-                        .unwrap();
-
-                    let bm = bm.lock().unwrap();
-
-                    print!("\r{:?} BLOCKS: {}", mined, bm.mined);
-                    std::io::stdout()
-                        .flush()
-                        .ok()
-                        .expect("Couldn't flush stdout!");
-                }
-                Err(_) => break,
-            }
-        }
+        let storage = storage::BlockStorage::open();
+        run_reporter(
+            rx,
+            save_file,
+            initial_totals,
+            json_output,
+            Some((block_manager, storage)),
+        );
     });
 
     for handle in handles {
         let _ = handle.join();
     }
 }
+
+fn run_server(args: Args) {
+    let save_file = Arc::new(Mutex::new(open_mined_log()));
+    let listen = args
+        .get("listen")
+        .cloned()
+        .unwrap_or_else(|| "0.0.0.0:3333".to_owned());
+
+    let (event_tx, _event_rx) = events::channel();
+    #[cfg(feature = "events")]
+    if is_json_output(&args) {
+        events::spawn_json_subscriber(_event_rx);
+    }
+
+    let (block_manager, initial_totals) = match storage::BlockStorage::restore() {
+        Some((block_manager, totals)) => {
+            println!("resuming from saved state");
+            (block_manager, totals)
+        }
+        None => (BlockManager::new(), HashMap::new()),
+    };
+    let block_manager = Arc::new(Mutex::new(block_manager));
+    let resource_manager = Arc::new(Mutex::new(ResourceMatcher::new(&args)));
+    let totals = Arc::new(Mutex::new(initial_totals));
+    let storage = Arc::new(Mutex::new(storage::BlockStorage::open()));
+
+    println!("start: {:#?}", get_time_ms());
+    println!("mode: coordinator");
+
+    net::run_coordinator(
+        &listen,
+        block_manager,
+        resource_manager,
+        save_file,
+        totals,
+        storage,
+        event_tx,
+    );
+}
+
+fn run_worker(args: Args) {
+    let save_file = open_mined_log();
+    let json_output = is_json_output(&args);
+    let connect = args
+        .get("connect")
+        .expect("-connect=host:port is required in worker mode")
+        .clone();
+    let resource_manager = Arc::new(Mutex::new(ResourceMatcher::new(&args)));
+    let threads = get_threads(&args);
+    let (tx, rx): (mpsc::Sender<(Resource, String)>, _) = mpsc::channel();
+    let (event_tx, _event_rx) = events::channel();
+    #[cfg(feature = "events")]
+    if json_output {
+        events::spawn_json_subscriber(_event_rx);
+    }
+
+    println!("start: {:#?}", get_time_ms());
+    println!("threads: {}", threads);
+    println!("mode: worker, connecting to {}", connect);
+    crate::emit_event!(event_tx, events::NodeEventType::MiningStarted { threads });
+
+    let reporter =
+        thread::spawn(move || run_reporter(rx, save_file, HashMap::new(), json_output, None));
+
+    net::run_worker(&connect, resource_manager, tx, threads, event_tx);
+    let _ = reporter.join();
+}
+
+fn main() {
+    let args = get_args();
+
+    match args.get("mode").map(|s| s.as_str()) {
+        Some("server") => run_server(args),
+        Some("worker") => run_worker(args),
+        _ => run_local(args),
+    }
+}
@@ -0,0 +1,257 @@
+#[cfg(feature = "events")]
+use crate::events;
+use crate::events::EventSender;
+use crate::storage::BlockStorage;
+use crate::{get_time_ms, Block, BlockManager, Nonce, Resource, ResourceKind, ResourceMatcher};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// A unit of work handed from the coordinator to a worker: scan `nonce_start..=nonce_end`
+/// of the block identified by `(created_at, seed)` against the coordinator's current
+/// per-kind `targets`, rather than the targets the worker's own matcher started with.
+#[derive(Debug, Serialize, Deserialize)]
+struct Job {
+    created_at: u128,
+    seed: [u8; 32],
+    nonce_start: Nonce,
+    nonce_end: Nonce,
+    targets: HashMap<ResourceKind, [u8; 32]>,
+}
+
+/// A worker's claim that `nonce` within the job's block matched `kind`.
+#[derive(Debug, Serialize, Deserialize)]
+struct Share {
+    seed: [u8; 32],
+    created_at: u128,
+    nonce: Nonce,
+    kind: ResourceKind,
+}
+
+/// Runs the `-mode=server` side: accepts worker connections, hands each one a job pulled
+/// from the shared `BlockManager`, and verifies/records any shares it sends back.
+pub(crate) fn run_coordinator(
+    addr: &str,
+    block_manager: Arc<Mutex<BlockManager>>,
+    resource_manager: Arc<Mutex<ResourceMatcher>>,
+    save_file: Arc<Mutex<std::fs::File>>,
+    totals: Arc<Mutex<HashMap<ResourceKind, u128>>>,
+    storage: Arc<Mutex<BlockStorage>>,
+    event_tx: EventSender,
+) {
+    let listener = TcpListener::bind(addr).expect("failed to bind coordinator socket");
+    println!("coordinator listening on {}", addr);
+
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(s) => s,
+            Err(_) => continue,
+        };
+
+        let bm = block_manager.clone();
+        let rm = resource_manager.clone();
+        let save_file = save_file.clone();
+        let totals = totals.clone();
+        let storage = storage.clone();
+        let event_tx = event_tx.clone();
+        thread::spawn(move || handle_worker(stream, bm, rm, save_file, totals, storage, event_tx));
+    }
+}
+
+fn handle_worker(
+    stream: TcpStream,
+    block_manager: Arc<Mutex<BlockManager>>,
+    resource_manager: Arc<Mutex<ResourceMatcher>>,
+    save_file: Arc<Mutex<std::fs::File>>,
+    totals: Arc<Mutex<HashMap<ResourceKind, u128>>>,
+    storage: Arc<Mutex<BlockStorage>>,
+    event_tx: EventSender,
+) {
+    let (block, nonce_start, nonce_end) = {
+        let mut bm = block_manager.lock().unwrap();
+        bm.get_block(&event_tx)
+    };
+    let targets = resource_manager.lock().unwrap().target_snapshot();
+
+    let job = Job {
+        created_at: block.created_at,
+        seed: block.seed,
+        nonce_start,
+        nonce_end,
+        targets,
+    };
+
+    let mut writer = match stream.try_clone() {
+        Ok(s) => s,
+        Err(_) => return,
+    };
+    if writeln!(writer, "{}", serde_json::to_string(&job).unwrap()).is_err() {
+        return;
+    }
+
+    let mut credited_nonces = HashSet::new();
+
+    for line in BufReader::new(stream).lines() {
+        let line = match line {
+            Ok(l) => l,
+            Err(_) => break,
+        };
+
+        let share: Share = match serde_json::from_str(&line) {
+            Ok(s) => s,
+            Err(_) => continue,
+        };
+
+        if share.seed != job.seed || share.created_at != job.created_at {
+            continue;
+        }
+
+        if share.nonce < job.nonce_start || share.nonce > job.nonce_end {
+            continue;
+        }
+
+        if !credited_nonces.insert(share.nonce) {
+            continue;
+        }
+
+        let claimed_block = Block {
+            created_at: share.created_at,
+            seed: share.seed,
+        };
+        let hash = claimed_block.hash(share.nonce);
+
+        let matched = { resource_manager.lock().unwrap().match_hash(hash.as_slice()) };
+        let resource = match matched {
+            Some(resource) if resource.kind == share.kind => resource,
+            _ => continue,
+        };
+
+        resource_manager
+            .lock()
+            .unwrap()
+            .record_find(&resource.kind, get_time_ms(), &event_tx);
+        crate::emit_event!(
+            event_tx,
+            events::NodeEventType::ResourceFound {
+                kind: resource.kind.clone(),
+                hash: hex::encode(&hash),
+                nonce: share.nonce,
+            }
+        );
+
+        {
+            let mut totals = totals.lock().unwrap();
+            *totals.entry(resource.kind.clone()).or_insert(0) += 1;
+
+            let bm = block_manager.lock().unwrap();
+            storage.lock().unwrap().checkpoint(&bm, &totals);
+        }
+
+        record_share(&save_file, &resource, &hash);
+    }
+}
+
+fn record_share(save_file: &Arc<Mutex<std::fs::File>>, resource: &Resource, hash: &[u8]) {
+    let mut save_file = save_file.lock().unwrap();
+    let _ = save_file.write_fmt(format_args!(
+        "{} {:?} {}\n",
+        get_time_ms(),
+        resource.kind,
+        hex::encode(hash)
+    ));
+}
+
+/// Runs the `-mode=worker` side: repeatedly connects to the coordinator, scans the job it
+/// is handed using the normal hashing loop, and reports hits back as shares.
+pub(crate) fn run_worker(
+    addr: &str,
+    resource_manager: Arc<Mutex<ResourceMatcher>>,
+    tx: mpsc::Sender<(Resource, String)>,
+    threads: usize,
+    event_tx: EventSender,
+) {
+    let mut handles = vec![];
+
+    for _ in 0..threads {
+        let rm = resource_manager.clone();
+        let tx = tx.clone();
+        let event_tx = event_tx.clone();
+        let addr = addr.to_owned();
+
+        handles.push(thread::spawn(move || loop {
+            if let Err(e) = run_worker_job(&addr, &rm, &tx, &event_tx) {
+                eprintln!("worker: {}, retrying...", e);
+                thread::sleep(Duration::from_secs(1));
+            }
+        }));
+    }
+
+    for handle in handles {
+        let _ = handle.join();
+    }
+}
+
+fn run_worker_job(
+    addr: &str,
+    resource_manager: &Arc<Mutex<ResourceMatcher>>,
+    tx: &mpsc::Sender<(Resource, String)>,
+    event_tx: &EventSender,
+) -> std::io::Result<()> {
+    let stream = TcpStream::connect(addr)?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = stream;
+
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let job: Job = serde_json::from_str(line.trim())
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+
+    // Match against the coordinator's current targets, not this worker's startup targets -
+    // the worker never retargets its own matcher, so as the coordinator retargets, shares
+    // found under the startup targets would get rejected as a different kind.
+    resource_manager.lock().unwrap().apply_targets(&job.targets);
+
+    let block = Block {
+        created_at: job.created_at,
+        seed: job.seed,
+    };
+
+    let mut n = job.nonce_start;
+    loop {
+        let hash = block.hash(n);
+
+        let matched = { resource_manager.lock().unwrap().match_hash(hash.as_slice()) };
+        if let Some(resource) = matched {
+            crate::emit_event!(
+                event_tx,
+                events::NodeEventType::ResourceFound {
+                    kind: resource.kind.clone(),
+                    hash: hex::encode(&hash),
+                    nonce: n,
+                }
+            );
+
+            let share = Share {
+                seed: block.seed,
+                created_at: block.created_at,
+                nonce: n,
+                kind: resource.kind.clone(),
+            };
+            writeln!(writer, "{}", serde_json::to_string(&share).unwrap())?;
+            let _ = tx.send((resource, hex::encode(hash)));
+        }
+
+        if n == job.nonce_end {
+            break;
+        }
+
+        n += 1;
+    }
+
+    Ok(())
+}
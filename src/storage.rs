@@ -0,0 +1,103 @@
+use crate::{Block, BlockManager, Nonce, ResourceKind};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::OpenOptions;
+use std::io::{BufRead, BufReader, Write};
+
+const STATE_FILE: &str = "miner.state";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct BlockCheckpoint {
+    created_at: u128,
+    seed: [u8; 32],
+    nonce: Nonce,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Checkpoint {
+    blocks: [BlockCheckpoint; 2],
+    mined: u128,
+    totals: HashMap<ResourceKind, u128>,
+}
+
+impl Checkpoint {
+    fn capture(block_manager: &BlockManager, totals: &HashMap<ResourceKind, u128>) -> Self {
+        let to_checkpoint = |(block, nonce): &(Block, Nonce)| BlockCheckpoint {
+            created_at: block.created_at,
+            seed: block.seed,
+            nonce: *nonce,
+        };
+
+        Self {
+            blocks: [
+                to_checkpoint(&block_manager.blocks[0]),
+                to_checkpoint(&block_manager.blocks[1]),
+            ],
+            mined: block_manager.mined,
+            totals: totals.clone(),
+        }
+    }
+
+    fn into_parts(self) -> (BlockManager, HashMap<ResourceKind, u128>) {
+        let from_checkpoint = |c: BlockCheckpoint| {
+            (
+                Block {
+                    created_at: c.created_at,
+                    seed: c.seed,
+                },
+                c.nonce,
+            )
+        };
+
+        let [a, b] = self.blocks;
+        let blocks = [from_checkpoint(a), from_checkpoint(b)];
+
+        (BlockManager::from_parts(blocks, self.mined), self.totals)
+    }
+}
+
+/// Append-only checkpoint log for `BlockManager` state, so a restart can resume scanning
+/// instead of starting both blocks over at nonce 0.
+pub(crate) struct BlockStorage {
+    file: std::fs::File,
+}
+
+impl BlockStorage {
+    pub(crate) fn open() -> Self {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(STATE_FILE)
+            .unwrap();
+
+        Self { file }
+    }
+
+    /// Reconstructs a `BlockManager` and its per-kind totals from the most recent
+    /// checkpoint in the state file, if one exists. The very last line can be a torn
+    /// write if the process was killed mid-`writeln!`, so this walks backwards from the
+    /// end and uses the last line that actually parses, rather than giving up and
+    /// discarding all prior progress.
+    pub(crate) fn restore() -> Option<(BlockManager, HashMap<ResourceKind, u128>)> {
+        let file = std::fs::File::open(STATE_FILE).ok()?;
+        let lines: Vec<String> = BufReader::new(file).lines().map_while(Result::ok).collect();
+
+        lines
+            .iter()
+            .rev()
+            .find_map(|line| serde_json::from_str::<Checkpoint>(line).ok())
+            .map(Checkpoint::into_parts)
+    }
+
+    /// Appends a snapshot of `block_manager` and `totals` to the state file.
+    pub(crate) fn checkpoint(
+        &mut self,
+        block_manager: &BlockManager,
+        totals: &HashMap<ResourceKind, u128>,
+    ) {
+        let checkpoint = Checkpoint::capture(block_manager, totals);
+
+        let _ = writeln!(self.file, "{}", serde_json::to_string(&checkpoint).unwrap());
+        let _ = self.file.flush();
+    }
+}